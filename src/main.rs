@@ -9,6 +9,7 @@
 // ─── Módulos ───
 
 mod provisioning;
+mod secure_session;
 mod secure_storage;
 mod wifi;
 
@@ -28,6 +29,7 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use secure_storage::SecureStorage;
+use wifi::MAX_CONNECT_FAILURES;
 
 // ─── Punto de entrada ───
 //
@@ -92,7 +94,47 @@ fn run() -> anyhow::Result<()> {
         info!("Connect to WiFi: 'Leonobitech-Setup' / Password: 'setup1234'");
         info!("Then open http://192.168.4.1 in your browser");
 
-        // start_provisioning() NUNCA retorna — reinicia el chip al completar
+        // Transporte de provisioning: SoftAP+HTTP por defecto, o BLE si se
+        // compila con la feature `prov-ble`. Ninguno de los dos retorna
+        // normalmente — reinician el chip al completar.
+        #[cfg(feature = "prov-ble")]
+        provisioning::start_provisioning_ble(storage)?;
+
+        #[cfg(not(feature = "prov-ble"))]
+        provisioning::start_provisioning(peripherals.modem, sysloop, storage)?;
+
+        return Ok(());
+    }
+
+    // ─── Check: ¿fallaron demasiadas conexiones seguidas? ───
+
+    let connect_failures = {
+        let storage = storage.lock().unwrap();
+        storage.get_connect_failures()?
+    };
+
+    if connect_failures >= MAX_CONNECT_FAILURES {
+        // Las credenciales guardadas están provocando fallos repetidos
+        // (AP cambió de password, se dio de baja, etc.). Seguir
+        // reintentando solo reinicia el chip en loop; en vez de eso,
+        // volvemos a modo provisioning para que el usuario las corrija.
+        warn!(
+            "{} consecutive STA connection failures, falling back to provisioning mode",
+            connect_failures
+        );
+
+        {
+            let mut storage = storage.lock().unwrap();
+            storage.reset_connect_failures()?;
+        }
+
+        info!("Connect to WiFi: 'Leonobitech-Setup' / Password: 'setup1234'");
+        info!("Then open http://192.168.4.1 in your browser");
+
+        #[cfg(feature = "prov-ble")]
+        provisioning::start_provisioning_ble(storage)?;
+
+        #[cfg(not(feature = "prov-ble"))]
         provisioning::start_provisioning(peripherals.modem, sysloop, storage)?;
 
         return Ok(());
@@ -110,17 +152,79 @@ fn run() -> anyhow::Result<()> {
     info!("Device ID: {}", credentials.device_id);
     info!("Connecting to WiFi: {}", credentials.wifi_ssid);
 
-    // wifi::connect() retorna Box<EspWifi> — debe mantenerse vivo.
-    // Si _wifi se dropea, la conexión WiFi se pierde (RAII).
-    let _wifi = wifi::connect(
-        &credentials.wifi_ssid,
-        &credentials.wifi_password,
-        peripherals.modem,
-        sysloop,
-    )?;
+    // from_fields() puede fallar si static_ip/gateway/netmask no parsean
+    // como Ipv4Addr. No la propagamos con `?` todavía: necesitamos que un
+    // error acá también cuente como fallo de conexión (ver match de abajo)
+    // para que MAX_CONNECT_FAILURES eventualmente se alcance y el
+    // dispositivo caiga a modo provisioning en vez de quedar en loop de
+    // reinicios reintentando el mismo parseo roto para siempre.
+    let static_ip_result = wifi::StaticIpConfig::from_fields(
+        &credentials.static_ip,
+        &credentials.gateway,
+        &credentials.netmask,
+        &credentials.dns,
+        &credentials.secondary_dns,
+    );
+
+    // Mismo razonamiento que con la IP estática: un valor roto acá no
+    // debería quedar reintentándose para siempre.
+    let power_save_result = wifi::PowerSaveMode::from_field(&credentials.power_save);
+
+    // Fast-connect: si ya conectamos exitosamente antes, el BSSID+canal
+    // cacheado nos deja saltar el scan() y ganar unos segundos de arranque.
+    let fast_connect = {
+        let storage = storage.lock().unwrap();
+        storage.load_fast_connect()?
+    };
+
+    // wifi::connect() retorna (Box<EspWifi>, EspSubscription, FastConnectInfo).
+    // Los primeros dos deben mantenerse vivos: si _wifi se dropea, la
+    // conexión se pierde (RAII); si _reconnect_sub se dropea, el handler
+    // de StaDisconnected se desuscribe y la reconexión automática deja de
+    // funcionar. El tercero lo persistimos para el fast-connect del
+    // próximo boot.
+    let connect_result = match (static_ip_result, power_save_result) {
+        (Ok(static_ip), Ok(power_save)) => wifi::connect(
+            &credentials.wifi_ssid,
+            &credentials.wifi_password,
+            peripherals.modem,
+            sysloop,
+            Some(nvs_partition.clone()),
+            static_ip,
+            fast_connect,
+            power_save,
+            storage.clone(),
+        ),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+
+    // Un fallo aquí no vuelve a modo provisioning en el acto: el modem ya
+    // fue consumido por wifi::connect(), así que no hay forma de
+    // reutilizarlo para levantar el SoftAP en este mismo boot. En cambio
+    // contamos el fallo y dejamos que main() reinicie el chip; si el
+    // contador llega a MAX_CONNECT_FAILURES, el próximo boot cae a
+    // provisioning antes de tocar el modem (ver el check más arriba).
+    let (_wifi, _reconnect_sub, connect_info) = match connect_result {
+        Ok(result) => {
+            let mut storage = storage.lock().unwrap();
+            storage.reset_connect_failures()?;
+            result
+        }
+        Err(e) => {
+            let mut storage = storage.lock().unwrap();
+            let failures = storage.record_connect_failure()?;
+            warn!("WiFi connect failed ({} consecutive failures): {:?}", failures, e);
+            return Err(e);
+        }
+    };
 
     info!("WiFi connected!");
 
+    {
+        let mut storage = storage.lock().unwrap();
+        storage.save_fast_connect(connect_info)?;
+    }
+
     // drop() explícito para zeroizar credenciales de memoria.
     // ZeroizeOnDrop sobreescribe los Strings con ceros antes de liberar.
     drop(credentials);
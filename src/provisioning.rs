@@ -13,7 +13,12 @@
 // 6. Al reiniciar, lee credenciales y conecta como Station
 
 use anyhow::Result;
-use embedded_svc::{http::Method, io::Write, ipv4 as embedded_ipv4};
+use embedded_svc::{
+    http::server::{Connection, Request},
+    http::Method,
+    io::Write,
+    ipv4 as embedded_ipv4,
+};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     hal::peripheral,
@@ -24,10 +29,14 @@ use esp_idf_svc::{
     },
 };
 use log::{error, info};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use crate::secure_session;
 use crate::secure_storage::{Credentials, SecureStorage};
+use crate::wifi::{self, ScanResult};
 
 // ─── Configuración del Access Point ───
 
@@ -36,6 +45,44 @@ const AP_PASSWORD: &str = "setup1234"; // Mínimo 8 chars para WPA2
 const AP_CHANNEL: u8 = 1;
 const AP_MAX_CONNECTIONS: u16 = 4;
 
+// Usuario/contraseña del handshake SEC2 — independientes de la contraseña
+// WiFi, solo autentican la sesión cifrada de provisioning. En producción
+// conviene generarlas en fábrica en vez de usar este valor por defecto.
+const PROV_USERNAME: &str = "setup";
+const PROV_DEFAULT_PASSWORD: &str = "setup1234";
+
+/// Tamaño máximo aceptado para el body de `/provision` — de sobra para
+/// SSID/password/device_id/api_key/static IP, pero evita que un body
+/// gigante (o infinito) agote la memoria del handler.
+const MAX_PROVISION_BODY: usize = 2048;
+
+// ─── Configuración del transporte BLE (feature `prov-ble`) ───
+
+#[cfg(feature = "prov-ble")]
+const BLE_DEVICE_NAME: &str = "Leonobitech-Setup";
+#[cfg(feature = "prov-ble")]
+const BLE_SERVICE_UUID: &str = "7a9e0001-6d6f-6269-6c65-6c656f6e6f62";
+#[cfg(feature = "prov-ble")]
+const BLE_CHAR_CREDENTIALS_UUID: &str = "7a9e0002-6d6f-6269-6c65-6c656f6e6f62"; // write
+#[cfg(feature = "prov-ble")]
+const BLE_CHAR_STATUS_UUID: &str = "7a9e0003-6d6f-6269-6c65-6c656f6e6f62"; // read
+#[cfg(feature = "prov-ble")]
+const BLE_CHAR_RESULT_UUID: &str = "7a9e0004-6d6f-6269-6c65-6c656f6e6f62"; // notify
+
+// ─── Configuración del portal cautivo ───
+
+const DNS_PORT: u16 = 53;
+const AP_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+
+/// URLs que iOS/Android/Windows usan para detectar si hay portal cautivo.
+/// Redirigirlas a "/" es lo que hace que el setup se abra solo al conectar.
+const CAPTIVE_PROBE_PATHS: [&str; 4] = [
+    "/generate_204",
+    "/hotspot-detect.html",
+    "/ncsi.txt",
+    "/connecttest.txt",
+];
+
 // ─── HTML del formulario de setup ───
 
 const HTML_FORM: &str = r#"<!DOCTYPE html>
@@ -49,17 +96,23 @@ const HTML_FORM: &str = r#"<!DOCTYPE html>
         h1 { color: #00d4ff; text-align: center; }
         form { background: #16213e; padding: 20px; border-radius: 10px; }
         label { display: block; margin: 15px 0 5px; color: #00d4ff; }
-        input { width: 100%; padding: 12px; border: 1px solid #0f3460; border-radius: 5px; background: #1a1a2e; color: #fff; box-sizing: border-box; }
+        input, select { width: 100%; padding: 12px; border: 1px solid #0f3460; border-radius: 5px; background: #1a1a2e; color: #fff; box-sizing: border-box; }
         button { width: 100%; padding: 15px; margin-top: 20px; background: #00d4ff; color: #1a1a2e; border: none; border-radius: 5px; font-weight: bold; cursor: pointer; }
         button:hover { background: #00a8cc; }
         .info { font-size: 12px; color: #888; margin-top: 5px; }
+        .link { font-size: 12px; color: #00d4ff; cursor: pointer; text-decoration: underline; }
+        #ssid-manual { display: none; margin-top: 10px; }
     </style>
 </head>
 <body>
     <h1>Leonobitech IoT</h1>
     <form method="POST" action="/provision">
         <label>WiFi Network (SSID)</label>
-        <input type="text" name="ssid" required maxlength="32">
+        <select id="ssid-select" name="ssid" required>
+            <option value="">Scanning for networks...</option>
+        </select>
+        <div class="info"><span class="link" onclick="showManualSsid()">Can't see your network? Enter it manually</span></div>
+        <input type="text" id="ssid-manual" name="ssid_manual" maxlength="32" placeholder="Hidden network SSID">
 
         <label>WiFi Password</label>
         <input type="password" name="password" required maxlength="64">
@@ -72,8 +125,66 @@ const HTML_FORM: &str = r#"<!DOCTYPE html>
         <input type="password" name="api_key" maxlength="128">
         <div class="info">Optional: For authenticated API calls</div>
 
+        <label>Static IP (optional)</label>
+        <input type="text" name="static_ip" maxlength="15" placeholder="e.g. 192.168.1.50">
+        <div class="info">Leave empty to use DHCP</div>
+
+        <label>Gateway (optional)</label>
+        <input type="text" name="gateway" maxlength="15" placeholder="e.g. 192.168.1.1">
+
+        <label>Netmask (optional)</label>
+        <input type="text" name="netmask" maxlength="15" placeholder="e.g. 255.255.255.0">
+
+        <label>DNS (optional)</label>
+        <input type="text" name="dns" maxlength="15" placeholder="e.g. 8.8.8.8">
+
+        <label>Secondary DNS (optional)</label>
+        <input type="text" name="secondary_dns" maxlength="15" placeholder="e.g. 8.8.4.4">
+
+        <label>WiFi Power-Save</label>
+        <select name="power_save">
+            <option value="default" selected>Default</option>
+            <option value="none">None (fastest, highest power draw)</option>
+            <option value="min_modem">Minimum modem sleep</option>
+            <option value="max_modem">Maximum modem sleep (lowest power)</option>
+        </select>
+        <div class="info">Battery-powered devices may want more sleep</div>
+
         <button type="submit">Save & Connect</button>
     </form>
+    <script>
+        function showManualSsid() {
+            var select = document.getElementById('ssid-select');
+            var manual = document.getElementById('ssid-manual');
+            select.removeAttribute('name');
+            select.disabled = true;
+            manual.style.display = 'block';
+            manual.setAttribute('name', 'ssid');
+            manual.required = true;
+        }
+
+        fetch('/scan')
+            .then(function (res) { return res.json(); })
+            .then(function (networks) {
+                var select = document.getElementById('ssid-select');
+                select.innerHTML = '';
+                if (networks.length === 0) {
+                    select.innerHTML = '<option value="">No networks found</option>';
+                    showManualSsid();
+                    return;
+                }
+                networks.forEach(function (net) {
+                    var option = document.createElement('option');
+                    option.value = net.ssid;
+                    option.textContent = net.ssid + ' (' + net.rssi + ' dBm, ' + net.auth + ')';
+                    select.appendChild(option);
+                });
+            })
+            .catch(function () {
+                document.getElementById('ssid-select').innerHTML = '<option value="">Scan failed</option>';
+                showManualSsid();
+            });
+    </script>
 </body>
 </html>"#;
 
@@ -144,9 +255,10 @@ pub fn start_provisioning(
 
     let ap_netif = EspNetif::new_with_conf(&ap_netif_config)?;
 
-    // wrap_all() combina driver + ambas interfaces en un EspWifi
+    // wrap_all() combina driver + ambas interfaces en un EspWifi.
+    // La interfaz STA queda arriba (sin conectar) solo para poder escanear
+    // redes cercanas desde el handler GET /scan.
     let mut wifi = EspWifi::wrap_all(driver, sta_netif, ap_netif)?;
-    let mut blocking_wifi = BlockingWifi::wrap(&mut wifi, sysloop)?;
 
     // Configurar el Access Point
     let ap_config = AccessPointConfiguration {
@@ -158,14 +270,28 @@ pub fn start_provisioning(
         ..Default::default()
     };
 
-    blocking_wifi.set_configuration(&Configuration::AccessPoint(ap_config))?;
-    blocking_wifi.start()?;
+    {
+        // BlockingWifi sólo se necesita para el setup inicial (start/config).
+        // Se dropea al final de este bloque para liberar el &mut wifi y
+        // poder mover `wifi` a un Arc<Mutex<..>> compartido con el scan.
+        let mut blocking_wifi = BlockingWifi::wrap(&mut wifi, sysloop.clone())?;
+        blocking_wifi.set_configuration(&Configuration::AccessPoint(ap_config))?;
+        blocking_wifi.start()?;
+    }
 
     // Esperar a que la interfaz de red esté lista
     std::thread::sleep(std::time::Duration::from_millis(500));
 
     info!("Provisioning mode active");
 
+    // Arc<Mutex<EspWifi>> para que el handler GET /scan pueda pedir un
+    // scan bajo demanda sin bloquear al resto del servidor.
+    let wifi = Arc::new(Mutex::new(wifi));
+
+    // Servidor DNS del portal cautivo: hace que el setup se abra solo
+    // al conectarse al AP, sin que el usuario tenga que escribir la IP.
+    let captive_dns = start_captive_dns()?;
+
     // ─── Flag de provisioning completado ───
 
     // Arc<Mutex<bool>> para compartir estado entre el handler HTTP y el loop principal.
@@ -189,74 +315,232 @@ pub fn start_provisioning(
         },
     )?;
 
-    // POST /provision → Procesar formulario
-    // `move` transfiere ownership de provisioned_clone y storage_clone al closure.
-    // Sin `move`, el closure intentaría tomar referencias — pero el closure
-    // vive más que la función actual, así que necesita ownership.
+    // GET /scan → Escanear redes WiFi cercanas y devolverlas como JSON
+    let wifi_clone = wifi.clone();
+    let scan_sysloop = sysloop.clone();
     server.fn_handler(
-        "/provision",
-        Method::Post,
-        move |mut req| -> core::result::Result<(), esp_idf_svc::io::EspIOError> {
-            // Leer body del POST (formulario URL-encoded)
-            let mut body = [0u8; 512];
-            let len = req.read(&mut body)?;
-            let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
-
-            // Parsear campos del formulario
-            let mut ssid = String::new();
-            let mut password = String::new();
-            let mut device_id = String::new();
-            let mut api_key = String::new();
-
-            for pair in body_str.split('&') {
-                if let Some((key, value)) = pair.split_once('=') {
-                    let decoded = urlencoding_decode(value);
-                    match key {
-                        "ssid" => ssid = decoded,
-                        "password" => password = decoded,
-                        "device_id" => device_id = decoded,
-                        "api_key" => api_key = decoded,
-                        _ => {}
+        "/scan",
+        Method::Get,
+        move |req| -> core::result::Result<(), esp_idf_svc::io::EspIOError> {
+            let networks = {
+                let mut wifi = wifi_clone.lock().unwrap();
+                match BlockingWifi::wrap(&mut wifi, scan_sysloop.clone()) {
+                    Ok(mut blocking_wifi) => wifi::scan(&mut blocking_wifi).unwrap_or_else(|e| {
+                        error!("WiFi scan failed: {:?}", e);
+                        Vec::new()
+                    }),
+                    Err(e) => {
+                        error!("Failed to prepare WiFi for scan: {:?}", e);
+                        Vec::new()
                     }
                 }
+            };
+
+            let json = scan_results_to_json(&networks);
+            let mut response = req.into_ok_response()?;
+            response.write_all(json.as_bytes())?;
+            Ok(())
+        },
+    )?;
+
+    // GET /generate_204, /hotspot-detect.html, /ncsi.txt, /connecttest.txt →
+    // Redirigir las URLs de detección de portal cautivo de iOS/Android/Windows
+    // hacia "/" para que el navegador del sistema abra el setup automáticamente.
+    for path in CAPTIVE_PROBE_PATHS {
+        server.fn_handler(
+            path,
+            Method::Get,
+            |req| -> core::result::Result<(), esp_idf_svc::io::EspIOError> {
+                let mut response =
+                    req.into_response(302, Some("Found"), &[("Location", "http://192.168.4.1/")])?;
+                response.write_all(b"Redirecting to setup...")?;
+                Ok(())
+            },
+        )?;
+    }
+
+    // ─── Sesión cifrada SEC2 (SRP6a + AES-256-GCM) ───
+    //
+    // Garantizar que el device tenga un salt+verifier SRP antes de abrir
+    // el servidor HTTP. Se genera una sola vez (primer boot en modo
+    // provisioning) a partir de PROV_USERNAME/PROV_DEFAULT_PASSWORD.
+    {
+        let mut storage_guard = storage.lock().unwrap();
+        if !storage_guard.has_secure_session_secret()? {
+            let mut salt = [0u8; secure_session::SALT_LEN];
+            unsafe {
+                esp_idf_svc::sys::esp_fill_random(salt.as_mut_ptr() as *mut _, salt.len() as u32);
             }
+            let verifier =
+                secure_session::compute_verifier(PROV_USERNAME, PROV_DEFAULT_PASSWORD, &salt);
+            storage_guard.store_secure_session_secret(&salt, &verifier.to_bytes_be())?;
+        }
+    }
 
-            // Validar campos requeridos
-            if ssid.is_empty() || password.is_empty() || device_id.is_empty() {
-                error!("Missing required fields in provisioning form");
+    // Arc<Mutex<Option<SrpSession>>>: el handshake vive entre /session0 y
+    // /session1, y la clave resultante la usa /provision para descifrar.
+    let srp_session: Arc<Mutex<Option<secure_session::SrpSession>>> = Arc::new(Mutex::new(None));
+
+    // POST /session0 → cliente manda A, device responde salt + B
+    let srp_session_clone = srp_session.clone();
+    let storage_for_session = storage.clone();
+    server.fn_handler(
+        "/session0",
+        Method::Post,
+        move |mut req| -> core::result::Result<(), esp_idf_svc::io::EspIOError> {
+            let body = match read_bounded_body(&mut req, MAX_PROVISION_BODY)? {
+                Some(body) => body,
+                None => {
+                    error!("/session0 body exceeds {} bytes", MAX_PROVISION_BODY);
+                    let mut response = req.into_status_response(413)?;
+                    response.write_all(b"Payload too large")?;
+                    return Ok(());
+                }
+            };
+            let a_pub_bytes = &body[..];
+
+            let (salt, verifier) = match storage_for_session.lock().unwrap().load_secure_session_secret() {
+                Ok(pair) => pair,
+                Err(e) => {
+                    error!("Failed to load SRP secret: {:?}", e);
+                    let mut response = req.into_status_response(500)?;
+                    response.write_all(b"Session secret unavailable")?;
+                    return Ok(());
+                }
+            };
+
+            let mut session = secure_session::SrpSession::new(salt, verifier);
+            if let Err(e) = session.set_client_public(a_pub_bytes) {
+                error!("SRP session0 failed: {:?}", e);
                 let mut response = req.into_status_response(400)?;
-                response.write_all(b"Missing required fields")?;
+                response.write_all(b"Invalid client public value")?;
                 return Ok(());
             }
 
-            // Crear credenciales y guardar en NVS
-            let creds = Credentials {
-                wifi_ssid: ssid,
-                wifi_password: password,
-                api_key,
-                device_id,
+            // Respuesta: salt || B — el cliente conoce SALT_LEN de antemano.
+            let mut payload = session.salt().to_vec();
+            payload.extend_from_slice(&session.b_pub_bytes());
+
+            *srp_session_clone.lock().unwrap() = Some(session);
+
+            let mut response = req.into_ok_response()?;
+            response.write_all(&payload)?;
+            Ok(())
+        },
+    )?;
+
+    // POST /session1 → cliente manda M1, device verifica y responde M2
+    let srp_session_clone = srp_session.clone();
+    server.fn_handler(
+        "/session1",
+        Method::Post,
+        move |mut req| -> core::result::Result<(), esp_idf_svc::io::EspIOError> {
+            let body = match read_bounded_body(&mut req, MAX_PROVISION_BODY)? {
+                Some(body) => body,
+                None => {
+                    error!("/session1 body exceeds {} bytes", MAX_PROVISION_BODY);
+                    let mut response = req.into_status_response(413)?;
+                    response.write_all(b"Payload too large")?;
+                    return Ok(());
+                }
             };
+            let m1 = &body[..];
 
-            if let Ok(mut storage) = storage_clone.lock() {
-                if let Err(e) = storage.store_credentials(creds) {
-                    error!("Failed to store credentials: {:?}", e);
-                    let mut response = req.into_status_response(500)?;
-                    response.write_all(b"Failed to store credentials")?;
+            let guard = srp_session_clone.lock().unwrap();
+            let session = match guard.as_ref() {
+                Some(session) => session,
+                None => {
+                    drop(guard);
+                    error!("/session1 called before /session0");
+                    let mut response = req.into_status_response(400)?;
+                    response.write_all(b"Call /session0 first")?;
                     return Ok(());
                 }
-            }
+            };
 
-            // Marcar provisioning como completado
-            if let Ok(mut p) = provisioned_clone.lock() {
-                *p = true;
+            match session.verify_client_proof(m1) {
+                Ok(m2) => {
+                    let mut response = req.into_ok_response()?;
+                    response.write_all(&m2)?;
+                    Ok(())
+                }
+                Err(e) => {
+                    error!("SRP session1 failed: {:?}", e);
+                    drop(guard);
+                    *srp_session_clone.lock().unwrap() = None;
+                    let mut response = req.into_status_response(401)?;
+                    response.write_all(b"Proof of password mismatch")?;
+                    Ok(())
+                }
             }
+        },
+    )?;
 
-            // Enviar página de éxito
-            let mut response = req.into_ok_response()?;
-            response.write_all(HTML_SUCCESS.as_bytes())?;
+    // POST /provision → Procesar formulario (cifrado con la clave de sesión
+    // SEC2, salvo que se compile con la feature `prov-plaintext` para
+    // pruebas de laboratorio).
+    // `move` transfiere ownership de provisioned_clone y storage_clone al closure.
+    // Sin `move`, el closure intentaría tomar referencias — pero el closure
+    // vive más que la función actual, así que necesita ownership.
+    #[cfg(feature = "prov-plaintext")]
+    server.fn_handler(
+        "/provision",
+        Method::Post,
+        move |mut req| -> core::result::Result<(), esp_idf_svc::io::EspIOError> {
+            // Leer body del POST (formulario URL-encoded, en claro)
+            let body = match read_bounded_body(&mut req, MAX_PROVISION_BODY)? {
+                Some(body) => body,
+                None => {
+                    error!("/provision body exceeds {} bytes", MAX_PROVISION_BODY);
+                    let mut response = req.into_status_response(413)?;
+                    response.write_all(b"Payload too large")?;
+                    return Ok(());
+                }
+            };
+            let body_str = std::str::from_utf8(&body).unwrap_or("");
 
-            info!("Provisioning complete! Device will restart.");
-            Ok(())
+            handle_provision_fields(req, body_str, &storage_clone, &provisioned_clone)
+        },
+    )?;
+
+    #[cfg(not(feature = "prov-plaintext"))]
+    server.fn_handler(
+        "/provision",
+        Method::Post,
+        move |mut req| -> core::result::Result<(), esp_idf_svc::io::EspIOError> {
+            // Leer el payload cifrado: nonce(12) || ciphertext+tag
+            let payload = match read_bounded_body(&mut req, MAX_PROVISION_BODY)? {
+                Some(payload) => payload,
+                None => {
+                    error!("/provision body exceeds {} bytes", MAX_PROVISION_BODY);
+                    let mut response = req.into_status_response(413)?;
+                    response.write_all(b"Payload too large")?;
+                    return Ok(());
+                }
+            };
+
+            let session_key = match srp_session.lock().unwrap().as_ref().and_then(|s| s.session_key()) {
+                Some(key) => key,
+                None => {
+                    error!("/provision called without a completed SEC2 session");
+                    let mut response = req.into_status_response(401)?;
+                    response.write_all(b"Complete /session0 + /session1 first")?;
+                    return Ok(());
+                }
+            };
+
+            let plaintext = match secure_session::decrypt_provision_body(&session_key, &payload) {
+                Ok(plaintext) => plaintext,
+                Err(e) => {
+                    error!("Failed to decrypt /provision body: {:?}", e);
+                    let mut response = req.into_status_response(400)?;
+                    response.write_all(b"Failed to decrypt provisioning payload")?;
+                    return Ok(());
+                }
+            };
+            let body_str = std::str::from_utf8(&plaintext).unwrap_or("");
+
+            handle_provision_fields(req, body_str, &storage_clone, &provisioned_clone)
         },
     )?;
 
@@ -272,6 +556,11 @@ pub fn start_provisioning(
             if *p {
                 info!("Provisioning completed, restarting in 3 seconds...");
                 std::thread::sleep(std::time::Duration::from_secs(3));
+
+                // Detener el hilo DNS y esperar a que libere el socket UDP
+                // antes de reiniciar.
+                captive_dns.stop();
+
                 unsafe {
                     esp_idf_svc::sys::esp_restart();
                 }
@@ -280,28 +569,493 @@ pub fn start_provisioning(
     }
 }
 
+// ─── Lectura robusta del body de un POST ───
+
+/// Lee el body completo de una request, honrando `Content-Length` en vez
+/// de asumir que entra en un solo `read()`. Devuelve `None` si el body
+/// (según `Content-Length` o según lo efectivamente leído) supera
+/// `max_len`, para que el caller responda 413 sin parsear un prefijo
+/// truncado.
+fn read_bounded_body<C>(
+    req: &mut Request<C>,
+    max_len: usize,
+) -> core::result::Result<Option<Vec<u8>>, esp_idf_svc::io::EspIOError>
+where
+    C: Connection<Error = esp_idf_svc::io::EspIOError>,
+{
+    if let Some(declared_len) = req
+        .header("Content-Length")
+        .and_then(|value| value.parse::<usize>().ok())
+    {
+        if declared_len > max_len {
+            return Ok(None);
+        }
+    }
+
+    let mut body = Vec::new();
+    let mut chunk = [0u8; 256];
+
+    loop {
+        let read = req.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+
+        body.extend_from_slice(&chunk[..read]);
+        if body.len() > max_len {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(body))
+}
+
+// ─── Procesamiento compartido del formulario de provisioning ───
+
+/// Parsea los campos `ssid`/`password`/`device_id`/`api_key` de un body
+/// URL-encoded ya decodificado (en claro, venga de la ruta plaintext o de
+/// descifrar el payload SEC2) y guarda las credenciales en NVS.
+///
+/// Compartido por los dos handlers de `/provision` (con y sin la feature
+/// `prov-plaintext`) para no duplicar el parseo y la validación.
+fn handle_provision_fields<C>(
+    mut req: Request<C>,
+    body_str: &str,
+    storage: &Arc<Mutex<SecureStorage>>,
+    provisioned: &Arc<Mutex<bool>>,
+) -> core::result::Result<(), esp_idf_svc::io::EspIOError>
+where
+    C: Connection<Error = esp_idf_svc::io::EspIOError>,
+{
+    // Parsear campos del formulario
+    let mut ssid = String::new();
+    let mut password = String::new();
+    let mut device_id = String::new();
+    let mut api_key = String::new();
+    let mut static_ip = String::new();
+    let mut gateway = String::new();
+    let mut netmask = String::new();
+    let mut dns = String::new();
+    let mut secondary_dns = String::new();
+    let mut power_save = String::new();
+
+    for pair in body_str.split('&') {
+        if let Some((key, value)) = pair.split_once('=') {
+            let decoded = urlencoding_decode(value);
+            match key {
+                "ssid" => ssid = decoded,
+                "password" => password = decoded,
+                "device_id" => device_id = decoded,
+                "api_key" => api_key = decoded,
+                "static_ip" => static_ip = decoded,
+                "gateway" => gateway = decoded,
+                "netmask" => netmask = decoded,
+                "dns" => dns = decoded,
+                "secondary_dns" => secondary_dns = decoded,
+                "power_save" => power_save = decoded,
+                _ => {}
+            }
+        }
+    }
+
+    // Validar campos requeridos
+    if ssid.is_empty() || password.is_empty() || device_id.is_empty() {
+        error!("Missing required fields in provisioning form");
+        let mut response = req.into_status_response(400)?;
+        response.write_all(b"Missing required fields")?;
+        return Ok(());
+    }
+
+    // Validar IP estática: si se especificó, debe parsear (si no, un typo
+    // acá brickea el dispositivo en un loop de reinicios en el próximo
+    // boot, mucho antes de que el usuario pueda volver a entrar al portal).
+    // static_ip vacío es válido — significa "usar DHCP".
+    if let Err(e) =
+        wifi::StaticIpConfig::from_fields(&static_ip, &gateway, &netmask, &dns, &secondary_dns)
+    {
+        error!("Invalid static IP configuration: {:?}", e);
+        let mut response = req.into_status_response(400)?;
+        response.write_all(b"Invalid static IP, gateway, netmask or DNS")?;
+        return Ok(());
+    }
+
+    // Validar el modo de power-save elegido (mismo motivo: un valor
+    // inesperado acá no debe quedar guardado para romper el próximo boot).
+    if let Err(e) = wifi::PowerSaveMode::from_field(&power_save) {
+        error!("Invalid power-save mode: {:?}", e);
+        let mut response = req.into_status_response(400)?;
+        response.write_all(b"Invalid power-save mode")?;
+        return Ok(());
+    }
+
+    // Crear credenciales y guardar en NVS
+    let creds = Credentials {
+        wifi_ssid: ssid,
+        wifi_password: password,
+        api_key,
+        device_id,
+        static_ip,
+        gateway,
+        netmask,
+        dns,
+        secondary_dns,
+        power_save,
+    };
+
+    if let Ok(mut storage) = storage.lock() {
+        if let Err(e) = storage.store_credentials(creds) {
+            error!("Failed to store credentials: {:?}", e);
+            let mut response = req.into_status_response(500)?;
+            response.write_all(b"Failed to store credentials")?;
+            return Ok(());
+        }
+    }
+
+    // Marcar provisioning como completado
+    if let Ok(mut p) = provisioned.lock() {
+        *p = true;
+    }
+
+    // Enviar página de éxito
+    let mut response = req.into_ok_response()?;
+    response.write_all(HTML_SUCCESS.as_bytes())?;
+
+    info!("Provisioning complete! Device will restart.");
+    Ok(())
+}
+
+// ─── Portal cautivo: servidor DNS ───
+
+/// Handle del hilo que corre el servidor DNS del portal cautivo.
+/// Se puede detener y joinear limpiamente antes de `esp_restart()`.
+struct CaptiveDns {
+    stop_flag: Arc<AtomicBool>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl CaptiveDns {
+    fn stop(self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+/// Arranca un servidor DNS mínimo en `192.168.4.1:53` que responde a
+/// CUALQUIER pregunta con la IP del Access Point. Esto es lo que hace
+/// que el sistema operativo del teléfono detecte un portal cautivo y
+/// abra el setup automáticamente.
+fn start_captive_dns() -> Result<CaptiveDns> {
+    let socket = UdpSocket::bind((AP_IP, DNS_PORT))?;
+    // Timeout corto para poder revisar el stop_flag periódicamente.
+    socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let stop_flag_clone = stop_flag.clone();
+
+    let thread = std::thread::spawn(move || {
+        info!("Captive DNS server listening on {}:{}", AP_IP, DNS_PORT);
+        let mut buf = [0u8; 512];
+
+        while !stop_flag_clone.load(Ordering::Relaxed) {
+            match socket.recv_from(&mut buf) {
+                Ok((len, src)) => {
+                    if let Some(response) = build_dns_response(&buf[..len]) {
+                        if let Err(e) = socket.send_to(&response, src) {
+                            error!("Captive DNS send failed: {:?}", e);
+                        }
+                    }
+                }
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    continue; // nada recibido dentro del timeout, revisar stop_flag
+                }
+                Err(e) => error!("Captive DNS recv failed: {:?}", e),
+            }
+        }
+
+        info!("Captive DNS server stopped");
+    });
+
+    Ok(CaptiveDns { stop_flag, thread })
+}
+
+/// Construye una respuesta DNS que apunta cualquier pregunta a `AP_IP`.
+///
+/// Reutiliza la sección de pregunta de la query tal cual (solo soportamos
+/// una pregunta por paquete, que es lo único que mandan los clientes reales)
+/// y le agrega un único registro A con TTL corto.
+fn build_dns_response(query: &[u8]) -> Option<Vec<u8>> {
+    // Header DNS = 12 bytes fijos.
+    if query.len() < 12 {
+        return None;
+    }
+
+    let mut response = Vec::with_capacity(query.len() + 16);
+
+    response.extend_from_slice(&query[0..2]); // ID: igual que la query
+
+    // Byte de flags alto: QR=1 (es una respuesta), Opcode y RD se heredan
+    // de la query, AA=1 (somos autoritativos para todo).
+    response.push((query[2] | 0b1000_0000) | 0b0000_0100);
+    // Byte de flags bajo: RA=0, Z=0, RCODE=0 (sin error).
+    response.push(0x00);
+
+    response.extend_from_slice(&query[4..6]); // QDCOUNT: igual que la query
+    response.extend_from_slice(&[0x00, 0x01]); // ANCOUNT: 1 respuesta
+    response.extend_from_slice(&[0x00, 0x00]); // NSCOUNT: 0
+    response.extend_from_slice(&[0x00, 0x00]); // ARCOUNT: 0
+
+    // Sección de pregunta: la copiamos íntegra de la query original.
+    response.extend_from_slice(&query[12..]);
+
+    // Registro de respuesta: apunta el nombre preguntado (vía puntero de
+    // compresión a offset 12) a AP_IP.
+    response.extend_from_slice(&[0xc0, 0x0c]); // NAME: puntero a offset 12
+    response.extend_from_slice(&[0x00, 0x01]); // TYPE: A
+    response.extend_from_slice(&[0x00, 0x01]); // CLASS: IN
+    response.extend_from_slice(&[0x00, 0x00, 0x00, 0x3c]); // TTL: 60s
+    response.extend_from_slice(&[0x00, 0x04]); // RDLENGTH: 4 bytes
+    response.extend_from_slice(&AP_IP.octets()); // RDATA: la IP del AP
+
+    Some(response)
+}
+
+// ─── Serialización de resultados de scan ───
+
+/// Convierte una lista de `ScanResult` al JSON que consume el `fetch()`
+/// del formulario: `[{"ssid":"...","rssi":-62,"auth":"WPA2"}]`.
+fn scan_results_to_json(networks: &[ScanResult]) -> String {
+    let mut json = String::from("[");
+    for (i, net) in networks.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str(&format!(
+            r#"{{"ssid":"{}","rssi":{},"auth":"{}"}}"#,
+            json_escape(&net.ssid),
+            net.rssi,
+            auth_method_label(net.auth_method)
+        ));
+    }
+    json.push(']');
+    json
+}
+
+/// Etiqueta legible para mostrar en el dropdown del portal.
+fn auth_method_label(auth_method: AuthMethod) -> &'static str {
+    match auth_method {
+        AuthMethod::None => "Open",
+        AuthMethod::WEP => "WEP",
+        AuthMethod::WPA => "WPA",
+        AuthMethod::WPA2Personal => "WPA2",
+        AuthMethod::WPAWPA2Personal => "WPA/WPA2",
+        AuthMethod::WPA3Personal => "WPA3",
+        AuthMethod::WPA2WPA3Personal => "WPA2/WPA3",
+        AuthMethod::WAPIPersonal => "WAPI",
+        _ => "WPA2",
+    }
+}
+
+/// Escapa comillas y backslashes para poder incrustar un string en JSON.
+fn json_escape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
 // ─── URL Decoding ───
 
-/// Decodifica URL encoding simple (maneja %XX y + como espacio).
+/// Decodifica URL encoding (maneja %XX y + como espacio).
 ///
 /// Los formularios HTML envían datos como "ssid=Mi+Red&password=abc%21".
-/// Esta función convierte eso de vuelta a texto legible.
+/// Acumulamos los bytes decodificados en un `Vec<u8>` y convertimos a
+/// `String` una sola vez al final — decodificar %XX directamente a `char`
+/// (como `byte as char`) rompe cualquier SSID/password no-ASCII, porque
+/// un carácter UTF-8 multi-byte (ej. "é" = %C3%A9) queda partido en dos
+/// `char` en vez de recombinarse en un solo punto de código.
 fn urlencoding_decode(input: &str) -> String {
-    let mut result = String::with_capacity(input.len());
-    let mut chars = input.chars().peekable();
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut iter = input.bytes();
 
-    while let Some(c) = chars.next() {
-        match c {
-            '+' => result.push(' '),
-            '%' => {
-                let hex: String = chars.by_ref().take(2).collect();
-                if let Ok(byte) = u8::from_str_radix(&hex, 16) {
-                    result.push(byte as char);
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next(), iter.next()];
+                match hex {
+                    [Some(hi), Some(lo)] => match u8::from_str_radix(
+                        std::str::from_utf8(&[hi, lo]).unwrap_or_default(),
+                        16,
+                    ) {
+                        Ok(byte) => bytes.push(byte),
+                        Err(_) => bytes.extend_from_slice(&[b'%', hi, lo]),
+                    },
+                    _ => bytes.push(b'%'), // "%" truncado al final del input
                 }
             }
-            _ => result.push(c),
+            _ => bytes.push(b),
         }
     }
 
-    result
+    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
 }
+
+// ─── Transporte BLE (feature `prov-ble`) ───
+//
+// Alternativa al portal SoftAP para teléfonos que no mantienen la
+// conexión a un Access Point sin gateway a internet. Expone un servicio
+// GATT con tres características:
+// - CREDENTIALS (write): recibe SSID/password/device_id/api_key, cada
+//   campo precedido por un byte de longitud.
+// - STATUS (read): expone si el device ya está provisionado.
+// - RESULT (notify): avisa a la app si el provisioning salió bien o mal.
+//
+// Igual que el flujo HTTP, al recibir credenciales válidas se guardan con
+// `SecureStorage::store_credentials` y se llama a `esp_restart()`.
+#[cfg(feature = "prov-ble")]
+mod ble {
+    use super::*;
+    use esp32_nimble::{uuid128, BLEDevice, NimbleProperties};
+
+    const RESULT_OK: u8 = 1;
+    const RESULT_FAILED: u8 = 0;
+
+    /// Inicia el transporte BLE del provisioning. Al igual que
+    /// `start_provisioning`, nunca retorna normalmente: reinicia el chip
+    /// al completar.
+    pub fn start_provisioning_ble(storage: Arc<Mutex<SecureStorage>>) -> Result<()> {
+        info!("Starting BLE provisioning mode...");
+
+        let device = BLEDevice::take();
+        let server = device.get_server();
+        let service = server.create_service(uuid128!(BLE_SERVICE_UUID));
+
+        // CREDENTIALS (write): SSID/password/device_id/api_key con
+        // longitud en el primer byte de cada campo.
+        let credentials_char = service.lock().create_characteristic(
+            uuid128!(BLE_CHAR_CREDENTIALS_UUID),
+            NimbleProperties::WRITE,
+        );
+
+        // STATUS (read): 1 = provisionado, 0 = esperando credenciales.
+        let status_char = service
+            .lock()
+            .create_characteristic(uuid128!(BLE_CHAR_STATUS_UUID), NimbleProperties::READ);
+        status_char.lock().set_value(&[0]);
+
+        // RESULT (notify): resultado de la escritura de credenciales.
+        let result_char = service.lock().create_characteristic(
+            uuid128!(BLE_CHAR_RESULT_UUID),
+            NimbleProperties::NOTIFY,
+        );
+
+        credentials_char.lock().on_write(move |args| {
+            let payload = args.recv_data();
+            info!("BLE write received: {} bytes", payload.len());
+
+            match parse_length_prefixed_credentials(payload) {
+                Ok(creds) => {
+                    let stored = storage
+                        .lock()
+                        .unwrap()
+                        .store_credentials(creds)
+                        .map_err(|e| error!("Failed to store BLE credentials: {:?}", e));
+
+                    if stored.is_ok() {
+                        status_char.lock().set_value(&[1]);
+                        result_char.lock().set_value(&[RESULT_OK]).notify();
+                        info!("BLE provisioning complete! Device will restart.");
+
+                        std::thread::spawn(|| {
+                            std::thread::sleep(Duration::from_secs(3));
+                            unsafe {
+                                esp_idf_svc::sys::esp_restart();
+                            }
+                        });
+                    } else {
+                        result_char.lock().set_value(&[RESULT_FAILED]).notify();
+                    }
+                }
+                Err(e) => {
+                    error!("Malformed BLE credentials payload: {:?}", e);
+                    result_char.lock().set_value(&[RESULT_FAILED]).notify();
+                }
+            }
+        });
+
+        let advertising = device.get_advertising();
+        advertising.lock().name(BLE_DEVICE_NAME);
+        advertising
+            .lock()
+            .add_service_uuid(uuid128!(BLE_SERVICE_UUID));
+        advertising.lock().start()?;
+
+        info!("BLE provisioning active, waiting for credentials...");
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    /// Parsea SSID/password/device_id/api_key de un payload donde cada
+    /// campo viene precedido por un byte con su longitud en bytes.
+    fn parse_length_prefixed_credentials(data: &[u8]) -> Result<Credentials> {
+        let mut cursor = data;
+        let ssid = read_length_prefixed_field(&mut cursor)?;
+        let password = read_length_prefixed_field(&mut cursor)?;
+        let device_id = read_length_prefixed_field(&mut cursor)?;
+        let api_key = read_length_prefixed_field(&mut cursor)?;
+
+        if ssid.is_empty() || password.is_empty() || device_id.is_empty() {
+            anyhow::bail!("Missing required fields in BLE credentials payload");
+        }
+
+        // El prefijo de longitud es un u8 (hasta 255 bytes por campo), pero
+        // `wifi::connect()` mete ssid/password en un `ClientConfiguration`
+        // cuyos campos son heapless strings de 32/64 bytes — si no
+        // validamos acá, un payload BLE fuera de rango queda guardado en
+        // NVS y el próximo boot panickea en el `.try_into().expect(...)`
+        // de wifi.rs, reiniciando el chip en loop sin pasar por
+        // MAX_CONNECT_FAILURES (los paniques no son errores `Result`).
+        if ssid.len() > 32 {
+            anyhow::bail!("SSID too long for BLE credentials payload (max 32 bytes)");
+        }
+        if password.len() > 64 {
+            anyhow::bail!("Password too long for BLE credentials payload (max 64 bytes)");
+        }
+
+        Ok(Credentials {
+            wifi_ssid: ssid,
+            wifi_password: password,
+            device_id,
+            api_key,
+            ..Default::default()
+        })
+    }
+
+    fn read_length_prefixed_field(cursor: &mut &[u8]) -> Result<String> {
+        let (&len, rest) = cursor
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Truncated BLE credentials payload"))?;
+        let len = len as usize;
+
+        if rest.len() < len {
+            anyhow::bail!("BLE credentials field length exceeds remaining payload");
+        }
+
+        let (field, rest) = rest.split_at(len);
+        *cursor = rest;
+
+        Ok(String::from_utf8(field.to_vec())?)
+    }
+}
+
+#[cfg(feature = "prov-ble")]
+pub use ble::start_provisioning_ble;
@@ -0,0 +1,180 @@
+// ─── Paso 2: Módulo Secure Session — Handshake SRP6a (protocomm SEC2) ───
+//
+// Cifra la sesión de provisioning para que la contraseña WiFi nunca viaje
+// en claro por el SoftAP, siguiendo el mismo esquema que "security2" de
+// protocomm en ESP-IDF:
+//
+//   1. POST /session0: el cliente manda su público efímero A.
+//      El device responde con el salt guardado y su público efímero B.
+//   2. POST /session1: el cliente manda M1 (prueba de conocer la
+//      contraseña de provisioning). El device verifica M1 y responde M2.
+//   3. A partir de ahí, SHA-256(secreto compartido) se usa como clave
+//      AES-256-GCM para cifrar el body de /provision.
+//
+// La contraseña de provisioning es independiente de la contraseña WiFi:
+// solo protege este handshake, no se guarda nunca en claro (se guarda su
+// verifier SRP, ver `SecureStorage::store_secure_session_secret`).
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{bail, Result};
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Longitud del salt SRP que generamos para cada dispositivo.
+pub const SALT_LEN: usize = 16;
+
+// Grupo SRP de 1024 bits (RFC 5054, grupo N1024). Un handshake local
+// entre el teléfono y el SoftAP de setup no necesita un grupo más grande.
+const N_HEX: &str = "EEAF0AB9ADB38DD69C33F80AFA8FC5E86072618775FF3C0B9EA2314C9C256576D674DF7496EA81D3383B4813D692C6E0E0D5D8E250B98BE48E495C1D6089DAD15DC7D7B46154D6B6CE8EF4AD69B15D4982559B297BCF1885C529F566660E57EC68EDBC3C05726CC02FD4CBF4976EAA9AFD5138FE8376435B9FC61D2FC0EB06E3";
+const G: u8 = 2;
+
+fn group_n() -> BigUint {
+    BigUint::parse_bytes(N_HEX.as_bytes(), 16).expect("N de grupo SRP hardcodeado inválido")
+}
+
+fn group_g() -> BigUint {
+    BigUint::from(G)
+}
+
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// k = H(N | PAD(g)) — usamos SHA-256 en vez del SHA-1 de la RFC original
+/// para no sumar otra primitiva de hash al binario.
+fn k_multiplier() -> BigUint {
+    let n = group_n();
+    let n_bytes = n.to_bytes_be();
+    let g_padded = pad_to(&group_g(), n_bytes.len());
+    BigUint::from_bytes_be(&sha256(&[&n_bytes, &g_padded]))
+}
+
+fn pad_to(n: &BigUint, len: usize) -> Vec<u8> {
+    let bytes = n.to_bytes_be();
+    let mut padded = vec![0u8; len.saturating_sub(bytes.len())];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+/// Calcula `x = H(salt | H(username | ":" | password))` y el verifier
+/// `v = g^x mod N`. Se corre una sola vez, al generar el secreto de sesión
+/// (ver `SecureStorage::store_secure_session_secret`).
+pub fn compute_verifier(username: &str, password: &str, salt: &[u8]) -> BigUint {
+    let inner = sha256(&[username.as_bytes(), b":", password.as_bytes()]);
+    let x = BigUint::from_bytes_be(&sha256(&[salt, &inner]));
+    group_g().modpow(&x, &group_n())
+}
+
+/// Estado del handshake SRP del lado del device (servidor SRP).
+pub struct SrpSession {
+    salt: Vec<u8>,
+    verifier: BigUint,
+    b_priv: BigUint,
+    b_pub: BigUint,
+    a_pub: Option<BigUint>,
+    session_key: Option<[u8; 32]>,
+}
+
+impl SrpSession {
+    /// Arranca una sesión nueva: genera el efímero privado `b` y calcula
+    /// el público `B = (k*v + g^b) mod N`.
+    pub fn new(salt: Vec<u8>, verifier: Vec<u8>) -> Self {
+        let verifier = BigUint::from_bytes_be(&verifier);
+
+        let mut b_bytes = [0u8; 32];
+        unsafe {
+            esp_idf_svc::sys::esp_fill_random(b_bytes.as_mut_ptr() as *mut _, b_bytes.len() as u32);
+        }
+        let b_priv = BigUint::from_bytes_be(&b_bytes);
+
+        let n = group_n();
+        let b_pub = (k_multiplier() * &verifier + group_g().modpow(&b_priv, &n)) % &n;
+
+        Self {
+            salt,
+            verifier,
+            b_priv,
+            b_pub,
+            a_pub: None,
+            session_key: None,
+        }
+    }
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    pub fn b_pub_bytes(&self) -> Vec<u8> {
+        self.b_pub.to_bytes_be()
+    }
+
+    /// Procesa el público del cliente `A` (recibido en POST /session0) y
+    /// deriva la clave de sesión compartida `K = H(S)`.
+    pub fn set_client_public(&mut self, a_pub_bytes: &[u8]) -> Result<()> {
+        let a_pub = BigUint::from_bytes_be(a_pub_bytes);
+        let n = group_n();
+
+        if &a_pub % &n == BigUint::from(0u8) {
+            bail!("Invalid client public value A (A mod N == 0)");
+        }
+
+        let u = BigUint::from_bytes_be(&sha256(&[&a_pub.to_bytes_be(), &self.b_pub.to_bytes_be()]));
+
+        // S = (A * v^u)^b mod N
+        let v_u = self.verifier.modpow(&u, &n);
+        let s = ((&a_pub * &v_u) % &n).modpow(&self.b_priv, &n);
+
+        self.session_key = Some(sha256(&[&s.to_bytes_be()]));
+        self.a_pub = Some(a_pub);
+        Ok(())
+    }
+
+    /// Verifica `M1` enviado por el cliente (POST /session1) y, si
+    /// coincide, devuelve `M2` para que el cliente confirme la sesión.
+    pub fn verify_client_proof(&self, m1: &[u8]) -> Result<[u8; 32]> {
+        let (a_pub, key) = match (&self.a_pub, &self.session_key) {
+            (Some(a), Some(k)) => (a, k),
+            _ => bail!("set_client_public must run before verify_client_proof"),
+        };
+
+        // Comparación en tiempo constante: M1 prueba que el cliente conoce
+        // la contraseña de provisioning, así que un `!=` normal (que corta
+        // en el primer byte distinto) filtraría por timing cuántos bytes
+        // iniciales acertó un atacante cercano al SoftAP.
+        let expected_m1 = sha256(&[&a_pub.to_bytes_be(), &self.b_pub.to_bytes_be(), key]);
+        if expected_m1.as_slice().ct_eq(m1).unwrap_u8() == 0 {
+            bail!("Client proof (M1) mismatch — wrong provisioning password");
+        }
+
+        Ok(sha256(&[&a_pub.to_bytes_be(), &expected_m1, key]))
+    }
+
+    /// Clave de sesión derivada (disponible después de `set_client_public`).
+    pub fn session_key(&self) -> Option<[u8; 32]> {
+        self.session_key
+    }
+}
+
+/// Descifra el body de `/provision` con AES-256-GCM, usando la clave de
+/// sesión derivada del handshake SRP como clave simétrica.
+///
+/// El payload es `nonce (12 bytes) || ciphertext+tag`.
+pub fn decrypt_provision_body(session_key: &[u8; 32], payload: &[u8]) -> Result<Vec<u8>> {
+    if payload.len() < 12 {
+        bail!("Encrypted /provision body too short to contain a nonce");
+    }
+    let (nonce_bytes, ciphertext) = payload.split_at(12);
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(session_key));
+    let nonce = Nonce::from_slice(nonce_bytes);
+
+    cipher.decrypt(nonce, ciphertext).map_err(|_| {
+        anyhow::anyhow!("Failed to decrypt /provision body — bad session key or tampered payload")
+    })
+}
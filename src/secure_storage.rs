@@ -9,9 +9,12 @@
 
 use anyhow::{bail, Result};
 use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
+use esp_idf_svc::wifi::AuthMethod;
 use log::{info, warn};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
+use crate::wifi::FastConnectInfo;
+
 // ─── Constantes NVS ───
 
 const NVS_NAMESPACE: &str = "credentials";
@@ -20,6 +23,18 @@ const KEY_WIFI_PASS: &str = "wifi_pass";
 const KEY_API_KEY: &str = "api_key";
 const KEY_DEVICE_ID: &str = "device_id";
 const KEY_PROVISIONED: &str = "provisioned";
+const KEY_PROV_SALT: &str = "prov_salt";
+const KEY_PROV_VERIFIER: &str = "prov_verif";
+const KEY_STATIC_IP: &str = "static_ip";
+const KEY_GATEWAY: &str = "gateway";
+const KEY_NETMASK: &str = "netmask";
+const KEY_DNS: &str = "dns";
+const KEY_SECONDARY_DNS: &str = "dns2";
+const KEY_POWER_SAVE: &str = "power_save";
+const KEY_FAST_BSSID: &str = "fast_bssid";
+const KEY_FAST_CHANNEL: &str = "fast_chan";
+const KEY_FAST_AUTH: &str = "fast_auth";
+const KEY_CONNECT_FAILURES: &str = "conn_fails";
 
 // ─── Struct de credenciales con borrado seguro ───
 
@@ -34,6 +49,22 @@ pub struct Credentials {
     pub wifi_password: String,
     pub api_key: String,
     pub device_id: String,
+    /// IP fija para el modo Station. Vacío = usar DHCP (default).
+    pub static_ip: String,
+    /// Gateway de la red, solo se usa si `static_ip` no está vacío.
+    pub gateway: String,
+    /// Máscara de subred punteada (ej. "255.255.255.0"), solo se usa si
+    /// `static_ip` no está vacío.
+    pub netmask: String,
+    /// DNS primario, solo se usa si `static_ip` no está vacío. Vacío = no
+    /// especificar (usa lo que agregue la resolución del SO, o nada).
+    pub dns: String,
+    /// DNS secundario, mismo comportamiento que `dns`.
+    pub secondary_dns: String,
+    /// Modo de power-save WiFi ("", "default", "none", "min_modem" o
+    /// "max_modem" — ver `wifi::PowerSaveMode::from_field`). Vacío = no
+    /// tocar el power-save (comportamiento de siempre).
+    pub power_save: String,
 }
 
 // ─── Secure Storage Manager ───
@@ -80,6 +111,12 @@ impl SecureStorage {
         self.nvs.set_str(KEY_WIFI_PASS, &creds.wifi_password)?;
         self.nvs.set_str(KEY_API_KEY, &creds.api_key)?;
         self.nvs.set_str(KEY_DEVICE_ID, &creds.device_id)?;
+        self.nvs.set_str(KEY_STATIC_IP, &creds.static_ip)?;
+        self.nvs.set_str(KEY_GATEWAY, &creds.gateway)?;
+        self.nvs.set_str(KEY_NETMASK, &creds.netmask)?;
+        self.nvs.set_str(KEY_DNS, &creds.dns)?;
+        self.nvs.set_str(KEY_SECONDARY_DNS, &creds.secondary_dns)?;
+        self.nvs.set_str(KEY_POWER_SAVE, &creds.power_save)?;
 
         // Marcar como provisionado
         self.nvs.set_u8(KEY_PROVISIONED, 1)?;
@@ -125,10 +162,146 @@ impl SecureStorage {
             buf.zeroize();
         }
 
+        if let Some(val) = self.nvs.get_str(KEY_STATIC_IP, &mut buf)? {
+            creds.static_ip = val.trim_end_matches('\0').to_string();
+            buf.zeroize();
+        }
+
+        if let Some(val) = self.nvs.get_str(KEY_GATEWAY, &mut buf)? {
+            creds.gateway = val.trim_end_matches('\0').to_string();
+            buf.zeroize();
+        }
+
+        if let Some(val) = self.nvs.get_str(KEY_NETMASK, &mut buf)? {
+            creds.netmask = val.trim_end_matches('\0').to_string();
+            buf.zeroize();
+        }
+
+        if let Some(val) = self.nvs.get_str(KEY_DNS, &mut buf)? {
+            creds.dns = val.trim_end_matches('\0').to_string();
+            buf.zeroize();
+        }
+
+        if let Some(val) = self.nvs.get_str(KEY_SECONDARY_DNS, &mut buf)? {
+            creds.secondary_dns = val.trim_end_matches('\0').to_string();
+            buf.zeroize();
+        }
+
+        if let Some(val) = self.nvs.get_str(KEY_POWER_SAVE, &mut buf)? {
+            creds.power_save = val.trim_end_matches('\0').to_string();
+            buf.zeroize();
+        }
+
         info!("Credentials loaded from NVS");
         Ok(creds)
     }
 
+    /// Guarda BSSID + canal + auth method de la última conexión exitosa,
+    /// para que el próximo boot pueda saltarse el scan() de `wifi::connect`
+    /// (ver `FastConnectInfo`).
+    pub fn save_fast_connect(&mut self, info: FastConnectInfo) -> Result<()> {
+        self.nvs.set_raw(KEY_FAST_BSSID, &info.bssid)?;
+        self.nvs.set_u8(KEY_FAST_CHANNEL, info.channel)?;
+        self.nvs
+            .set_u8(KEY_FAST_AUTH, auth_method_to_u8(info.auth_method))?;
+        info!(
+            "Fast-connect info saved (BSSID {:02x?}, channel {})",
+            info.bssid, info.channel
+        );
+        Ok(())
+    }
+
+    /// Carga el fast-connect cacheado, si existe. `None` si nunca se
+    /// guardó uno (primer boot, o borrado junto con las credenciales).
+    pub fn load_fast_connect(&self) -> Result<Option<FastConnectInfo>> {
+        let mut bssid_buf = [0u8; 6];
+        let bssid = match self.nvs.get_raw(KEY_FAST_BSSID, &mut bssid_buf)? {
+            Some(raw) if raw.len() == 6 => {
+                let mut bssid = [0u8; 6];
+                bssid.copy_from_slice(raw);
+                bssid
+            }
+            _ => return Ok(None),
+        };
+
+        let Some(channel) = self.nvs.get_u8(KEY_FAST_CHANNEL)? else {
+            return Ok(None);
+        };
+
+        let auth_method = self
+            .nvs
+            .get_u8(KEY_FAST_AUTH)?
+            .map(auth_method_from_u8)
+            .unwrap_or(AuthMethod::WPA2Personal);
+
+        Ok(Some(FastConnectInfo {
+            bssid,
+            channel,
+            auth_method,
+        }))
+    }
+
+    /// Lee el contador de fallos consecutivos de conexión STA. 0 si nunca
+    /// falló o si se reseteó tras una conexión exitosa.
+    pub fn get_connect_failures(&self) -> Result<u8> {
+        Ok(self.nvs.get_u8(KEY_CONNECT_FAILURES)?.unwrap_or(0))
+    }
+
+    /// Incrementa el contador de fallos consecutivos y retorna el nuevo
+    /// valor. El caller lo compara contra un umbral para decidir si cae a
+    /// modo provisioning en vez de seguir reintentando STA (ver
+    /// `run()` en `main.rs`).
+    pub fn record_connect_failure(&mut self) -> Result<u8> {
+        let failures = self.get_connect_failures()?.saturating_add(1);
+        self.nvs.set_u8(KEY_CONNECT_FAILURES, failures)?;
+        Ok(failures)
+    }
+
+    /// Resetea el contador de fallos, normalmente tras una conexión
+    /// exitosa.
+    pub fn reset_connect_failures(&mut self) -> Result<()> {
+        self.nvs.set_u8(KEY_CONNECT_FAILURES, 0)?;
+        Ok(())
+    }
+
+    /// Verifica si ya existe un secreto de sesión segura (salt + verifier
+    /// SRP) para el handshake cifrado de provisioning (SEC2).
+    pub fn has_secure_session_secret(&self) -> Result<bool> {
+        let mut buf = [0u8; 64];
+        Ok(self.nvs.get_raw(KEY_PROV_SALT, &mut buf)?.is_some())
+    }
+
+    /// Guarda el salt y el verifier SRP derivados del usuario/contraseña
+    /// de provisioning (NO la contraseña WiFi). Se genera una sola vez,
+    /// normalmente la primera vez que el dispositivo entra en modo
+    /// provisioning.
+    pub fn store_secure_session_secret(&mut self, salt: &[u8], verifier: &[u8]) -> Result<()> {
+        self.nvs.set_raw(KEY_PROV_SALT, salt)?;
+        self.nvs.set_raw(KEY_PROV_VERIFIER, verifier)?;
+        info!("Secure session secret (SRP salt + verifier) stored");
+        Ok(())
+    }
+
+    /// Carga el salt y el verifier SRP guardados en NVS.
+    pub fn load_secure_session_secret(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut salt_buf = [0u8; 64];
+        let mut verifier_buf = [0u8; 256];
+
+        let salt = self
+            .nvs
+            .get_raw(KEY_PROV_SALT, &mut salt_buf)?
+            .map(|s| s.to_vec())
+            .ok_or_else(|| anyhow::anyhow!("No secure session salt stored in NVS"))?;
+
+        let verifier = self
+            .nvs
+            .get_raw(KEY_PROV_VERIFIER, &mut verifier_buf)?
+            .map(|v| v.to_vec())
+            .ok_or_else(|| anyhow::anyhow!("No secure session verifier stored in NVS"))?;
+
+        Ok((salt, verifier))
+    }
+
     /// Borra todas las credenciales de NVS (factory reset).
     ///
     /// Sobreescribe con strings vacíos antes de marcar como no provisionado.
@@ -140,9 +313,41 @@ impl SecureStorage {
         self.nvs.set_str(KEY_WIFI_PASS, "")?;
         self.nvs.set_str(KEY_API_KEY, "")?;
         self.nvs.set_str(KEY_DEVICE_ID, "")?;
+        self.nvs.set_str(KEY_STATIC_IP, "")?;
+        self.nvs.set_str(KEY_GATEWAY, "")?;
+        self.nvs.set_str(KEY_NETMASK, "")?;
+        self.nvs.set_str(KEY_DNS, "")?;
+        self.nvs.set_str(KEY_SECONDARY_DNS, "")?;
+        self.nvs.set_str(KEY_POWER_SAVE, "")?;
         self.nvs.set_u8(KEY_PROVISIONED, 0)?;
+        self.nvs.set_raw(KEY_FAST_BSSID, &[])?;
+        self.nvs.set_u8(KEY_FAST_CHANNEL, 0)?;
+        self.nvs.set_u8(KEY_FAST_AUTH, 0)?;
+        self.nvs.set_u8(KEY_CONNECT_FAILURES, 0)?;
 
         info!("Credentials cleared");
         Ok(())
     }
 }
+
+/// `AuthMethod` es un enum fieldless — el cast a `u8` es estable mientras
+/// no cambie el orden de las variantes, así que lo persistimos tal cual
+/// en vez de serializar el enum entero.
+fn auth_method_to_u8(method: AuthMethod) -> u8 {
+    method as u8
+}
+
+fn auth_method_from_u8(value: u8) -> AuthMethod {
+    match value {
+        v if v == AuthMethod::None as u8 => AuthMethod::None,
+        v if v == AuthMethod::WEP as u8 => AuthMethod::WEP,
+        v if v == AuthMethod::WPA as u8 => AuthMethod::WPA,
+        v if v == AuthMethod::WPA2Personal as u8 => AuthMethod::WPA2Personal,
+        v if v == AuthMethod::WPAWPA2Personal as u8 => AuthMethod::WPAWPA2Personal,
+        v if v == AuthMethod::WPA2Enterprise as u8 => AuthMethod::WPA2Enterprise,
+        v if v == AuthMethod::WPA3Personal as u8 => AuthMethod::WPA3Personal,
+        v if v == AuthMethod::WPA2WPA3Personal as u8 => AuthMethod::WPA2WPA3Personal,
+        v if v == AuthMethod::WAPIPersonal as u8 => AuthMethod::WAPIPersonal,
+        _ => AuthMethod::WPA2Personal,
+    }
+}
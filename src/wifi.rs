@@ -5,27 +5,347 @@
 // y retorna el driver WiFi conectado con IP asignada.
 
 use anyhow::{bail, Result};
+use embedded_svc::ipv4 as embedded_ipv4;
 use esp_idf_svc::{
-    eventloop::EspSystemEventLoop,
+    eventloop::{EspSystemEventLoop, System},
     hal::peripheral,
-    wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi},
+    nvs::EspDefaultNvsPartition,
+    wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi, WifiEvent},
 };
-use log::info;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::secure_storage::SecureStorage;
+
+// Después de esta cantidad de fallos consecutivos de conexión STA (boot-time
+// o reconexiones en segundo plano, ver `spawn_auto_reconnect`), asumimos que
+// las credenciales guardadas están rotas y caemos a modo provisioning en vez
+// de seguir reintentando para siempre.
+pub const MAX_CONNECT_FAILURES: u8 = 5;
+
+// ─── Reconexión automática ───
+
+/// Delay base del backoff exponencial al reconectar tras un
+/// `WifiEvent::StaDisconnected`. Se duplica por cada intento fallido
+/// consecutivo hasta `RECONNECT_MAX_DELAY`.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Suscribe al `EspSystemEventLoop` para reconectar solo cuando el STA se
+/// desconecta (`WifiEvent::StaDisconnected`), con backoff exponencial
+/// acotado. El contador de intentos se resetea en cada `StaConnected`.
+///
+/// Llama a `esp_wifi_connect()` directamente (FFI) en vez de pasar por
+/// `BlockingWifi`: el radio WiFi es un recurso único por chip y el
+/// callback del event loop no tiene forma de tomar el `&mut BlockingWifi`
+/// que `connect()` ya devolvió a su caller.
+///
+/// Sin esto, una STA que conectó una vez y luego pierde la red para
+/// siempre (router dado de baja, credenciales rotadas) reintentaría en
+/// segundo plano por siempre sin pasar nunca por `MAX_CONNECT_FAILURES`,
+/// que solo se evalúa en el `wifi::connect()` de arranque. Para evitar
+/// eso, cuando los intentos consecutivos llegan a `MAX_CONNECT_FAILURES`
+/// guardamos el fallo en el mismo contador persistente de NVS y
+/// reiniciamos el chip: el próximo boot lo va a encontrar ya por encima
+/// del umbral y caer directo a modo provisioning (ver el check en
+/// `main.rs::run()`).
+fn spawn_auto_reconnect(
+    sysloop: &EspSystemEventLoop,
+    storage: Arc<Mutex<SecureStorage>>,
+) -> Result<esp_idf_svc::eventloop::EspSubscription<'static, System>> {
+    let attempt = Arc::new(AtomicU32::new(0));
+    let attempt_disconnect = attempt.clone();
+    let storage_disconnect = storage.clone();
+
+    let subscription = sysloop.subscribe::<WifiEvent, _>(move |event| match event {
+        WifiEvent::StaDisconnected => {
+            let n = attempt_disconnect.fetch_add(1, Ordering::SeqCst);
+
+            if n + 1 >= MAX_CONNECT_FAILURES as u32 {
+                error!(
+                    "{} consecutive background reconnect failures, restarting to fall back to provisioning",
+                    n + 1
+                );
+
+                let storage = storage_disconnect.clone();
+                std::thread::spawn(move || {
+                    if let Ok(mut storage) = storage.lock() {
+                        if let Err(e) = storage.record_connect_failure() {
+                            error!("Failed to record connect failure before restart: {:?}", e);
+                        }
+                    }
+                    unsafe {
+                        esp_idf_svc::sys::esp_restart();
+                    }
+                });
+                return;
+            }
+
+            let delay = RECONNECT_BASE_DELAY
+                .saturating_mul(1 << n.min(6))
+                .min(RECONNECT_MAX_DELAY);
+
+            warn!(
+                "WiFi disconnected, reconnecting in {:?} (attempt {})",
+                delay,
+                n + 1
+            );
+
+            std::thread::spawn(move || {
+                std::thread::sleep(delay);
+                unsafe {
+                    esp_idf_svc::sys::esp_wifi_connect();
+                }
+            });
+        }
+        WifiEvent::StaConnected => {
+            attempt.store(0, Ordering::SeqCst);
+
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                if let Ok(mut storage) = storage.lock() {
+                    if let Err(e) = storage.reset_connect_failures() {
+                        error!("Failed to reset connect failures after reconnect: {:?}", e);
+                    }
+                }
+            });
+        }
+        _ => {}
+    })?;
+
+    Ok(subscription)
+}
+
+// ─── Configuración de IP estática ───
+
+/// IP fija para el modo Station, alternativa a la asignación por DHCP.
+///
+/// Construido a partir de los campos `static_ip`/`gateway`/`netmask` de
+/// `Credentials` — ver `StaticIpConfig::from_fields`. Los DNS son
+/// opcionales: si no se especifican, se deja lo que traiga la red (o
+/// ninguno, si la red no lo anuncia).
+#[derive(Debug, Clone, Copy)]
+pub struct StaticIpConfig {
+    pub address: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub dns: Option<Ipv4Addr>,
+    pub secondary_dns: Option<Ipv4Addr>,
+}
+
+impl StaticIpConfig {
+    /// Parsea los campos de texto guardados en NVS. Si `static_ip` está
+    /// vacío se interpreta como "usar DHCP" (comportamiento de siempre) y
+    /// retorna `None`. `dns`/`secondary_dns` vacíos son válidos y quedan
+    /// en `None` (usa lo que agregue la resolución del SO, o nada).
+    pub fn from_fields(
+        static_ip: &str,
+        gateway: &str,
+        netmask: &str,
+        dns: &str,
+        secondary_dns: &str,
+    ) -> Result<Option<Self>> {
+        if static_ip.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            address: static_ip.parse()?,
+            gateway: gateway.parse()?,
+            netmask: netmask.parse()?,
+            dns: parse_optional_ipv4(dns)?,
+            secondary_dns: parse_optional_ipv4(secondary_dns)?,
+        }))
+    }
+}
+
+/// Parsea un campo de texto opcional a `Ipv4Addr`. Vacío = `None`.
+fn parse_optional_ipv4(value: &str) -> Result<Option<Ipv4Addr>> {
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(value.parse()?))
+    }
+}
+
+/// Convierte una máscara punteada (ej. "255.255.255.0") a la longitud de
+/// prefijo que espera `embedded_svc::ipv4::Mask` (ej. 24).
+fn netmask_to_prefix_len(netmask: Ipv4Addr) -> u8 {
+    u32::from(netmask).count_ones() as u8
+}
+
+/// Aplica una IP fija sobre la interfaz STA, reemplazando el cliente DHCP
+/// por defecto. Compartido entre `connect()` y `connect_enterprise()`.
+fn apply_static_ip(wifi: &mut BlockingWifi<&mut EspWifi<'_>>, cfg: StaticIpConfig) -> Result<()> {
+    info!(
+        "Using static IP {} (gateway {}, mask {}, dns {:?})",
+        cfg.address, cfg.gateway, cfg.netmask, cfg.dns
+    );
+    wifi.wifi().sta_netif().set_ip_configuration(&embedded_ipv4::Configuration::Client(
+        embedded_ipv4::ClientConfiguration::Fixed(embedded_ipv4::ClientSettings {
+            ip: cfg.address,
+            subnet: embedded_ipv4::Subnet {
+                gateway: cfg.gateway,
+                mask: embedded_ipv4::Mask(netmask_to_prefix_len(cfg.netmask)),
+            },
+            dns: cfg.dns,
+            secondary_dns: cfg.secondary_dns,
+        }),
+    ))?;
+    Ok(())
+}
+
+// ─── Power-save ───
+
+/// Política de ahorro de energía del modem WiFi, aplicada vía
+/// `esp_wifi_set_ps()` (esp-idf-svc no expone un wrapper seguro para
+/// esto). Afecta cuánto duerme el radio entre beacons del AP: más sueño
+/// = menor consumo pero mayor latencia/jitter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerSaveMode {
+    /// No llama a `esp_wifi_set_ps()` — deja el default de ESP-IDF.
+    /// Comportamiento de siempre, para no afectar a callers existentes.
+    #[default]
+    Default,
+    /// `WIFI_PS_NONE`: el radio nunca duerme. Máxima responsividad,
+    /// máximo consumo.
+    None,
+    /// `WIFI_PS_MIN_MODEM`: duerme entre beacons del AP. Buen balance
+    /// latencia/consumo, es lo que la mayoría de STAs conectadas a
+    /// corriente no necesitan pero las a batería sí agradecen.
+    MinModem,
+    /// `WIFI_PS_MAX_MODEM`: duerme agresivamente (usa el DTIM interval
+    /// del AP). Mayor latencia, pensado para STA a batería donde el
+    /// consumo importa más que la responsividad.
+    MaxModem,
+}
+
+impl PowerSaveMode {
+    /// Parsea el valor guardado en NVS / enviado por el formulario de
+    /// provisioning (campo `power_save`). Vacío o "default" es válido y
+    /// significa "no tocar el power-save" (comportamiento de siempre).
+    pub fn from_field(value: &str) -> Result<Self> {
+        Ok(match value {
+            "" | "default" => PowerSaveMode::Default,
+            "none" => PowerSaveMode::None,
+            "min_modem" => PowerSaveMode::MinModem,
+            "max_modem" => PowerSaveMode::MaxModem,
+            other => bail!("Invalid power-save mode: '{}'", other),
+        })
+    }
+}
+
+/// Aplica la política de power-save, si corresponde (`PowerSaveMode::Default`
+/// no hace nada). Debe llamarse después de `wifi.start()` — ESP-IDF ignora
+/// `esp_wifi_set_ps()` si el driver todavía no arrancó.
+fn apply_power_save(mode: PowerSaveMode) -> Result<()> {
+    let ps_type = match mode {
+        PowerSaveMode::Default => return Ok(()),
+        PowerSaveMode::None => esp_idf_svc::sys::wifi_ps_type_t_WIFI_PS_NONE,
+        PowerSaveMode::MinModem => esp_idf_svc::sys::wifi_ps_type_t_WIFI_PS_MIN_MODEM,
+        PowerSaveMode::MaxModem => esp_idf_svc::sys::wifi_ps_type_t_WIFI_PS_MAX_MODEM,
+    };
+
+    info!("Setting WiFi power-save mode: {:?}", mode);
+    unsafe {
+        esp_idf_svc::sys::esp!(esp_idf_svc::sys::esp_wifi_set_ps(ps_type))?;
+    }
+    Ok(())
+}
+
+// ─── Fast-connect ───
+
+/// BSSID + canal + auth method de la última conexión exitosa, para
+/// saltarse el scan() en el siguiente boot. El caller (`main.rs`) es
+/// quien la persiste en NVS después de una conexión exitosa y la vuelve
+/// a pasar en el siguiente `connect()`.
+#[derive(Debug, Clone, Copy)]
+pub struct FastConnectInfo {
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub auth_method: AuthMethod,
+}
+
+// ─── Resultado de scan ───
+
+/// Red WiFi detectada durante un scan(), con lo mínimo para mostrarla al
+/// usuario (por ejemplo en el dropdown del portal de provisioning).
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    pub ssid: String,
+    pub rssi: i8,
+    pub auth_method: AuthMethod,
+}
+
+/// Escanea redes WiFi cercanas usando un `BlockingWifi` ya inicializado
+/// (por ejemplo la interfaz STA que queda disponible dentro del AP+STA
+/// combo de `wrap_all` durante el provisioning).
+///
+/// Deduplica por SSID quedándose con el RSSI más fuerte, ordena de mayor
+/// a menor señal y recorta a un máximo de 20 resultados.
+pub fn scan(wifi: &mut BlockingWifi<&mut EspWifi<'_>>) -> Result<Vec<ScanResult>> {
+    let ap_infos = wifi.scan()?;
+
+    let mut by_ssid: HashMap<String, ScanResult> = HashMap::new();
+    for ap in ap_infos {
+        let ssid = ap.ssid.as_str().to_string();
+        if ssid.is_empty() {
+            continue; // redes ocultas no tienen nombre que mostrar
+        }
+
+        let rssi = ap.signal_strength;
+        let auth_method = ap.auth_method.unwrap_or(AuthMethod::WPA2Personal);
+
+        by_ssid
+            .entry(ssid.clone())
+            .and_modify(|existing| {
+                if rssi > existing.rssi {
+                    existing.rssi = rssi;
+                    existing.auth_method = auth_method;
+                }
+            })
+            .or_insert(ScanResult {
+                ssid,
+                rssi,
+                auth_method,
+            });
+    }
+
+    let mut results: Vec<ScanResult> = by_ssid.into_values().collect();
+    results.sort_by(|a, b| b.rssi.cmp(&a.rssi));
+    results.truncate(20);
+
+    Ok(results)
+}
 
 /// Conecta el ESP32 a una red WiFi en modo Station.
 ///
-/// Retorna `Box<EspWifi<'static>>` — el driver WiFi en el heap.
-/// IMPORTANTE: mientras el Box exista, la conexión WiFi se mantiene.
-/// Si se dropea, la conexión se pierde (RAII).
+/// Retorna el driver WiFi (`Box<EspWifi<'static>>`, en el heap — mientras
+/// el Box exista la conexión se mantiene, RAII), la subscription de
+/// reconexión automática, y el `FastConnectInfo` de la conexión lograda
+/// (para que el caller lo persista y se lo pase de vuelta en el próximo
+/// boot vía `fast_connect`).
 pub fn connect(
     ssid: &str,
     password: &str,
     modem: impl peripheral::Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
     sysloop: EspSystemEventLoop,
-) -> Result<Box<EspWifi<'static>>> {
+    nvs: Option<EspDefaultNvsPartition>,
+    static_ip: Option<StaticIpConfig>,
+    fast_connect: Option<FastConnectInfo>,
+    power_save: PowerSaveMode,
+    storage: Arc<Mutex<SecureStorage>>,
+) -> Result<(
+    Box<EspWifi<'static>>,
+    esp_idf_svc::eventloop::EspSubscription<'static, System>,
+    FastConnectInfo,
+)> {
     // ─── Validación de credenciales ───
 
-    let mut auth_method = AuthMethod::WPA2Personal;
     if ssid.is_empty() {
         bail!("WiFi SSID not configured");
     }
@@ -34,20 +354,22 @@ pub fn connect(
     info!("WiFi password length: {} bytes", password.len());
 
     if password.is_empty() {
-        auth_method = AuthMethod::None;
         info!("WiFi password empty, using open network");
     }
 
     // ─── Crear driver WiFi ───
 
-    // EspWifi::new() toma ownership del modem — nadie más puede usar el radio.
+    // EspWifi::new() toma ownership del modem — nadie más usa el radio.
     // sysloop.clone() es barato: usa Arc internamente (solo incrementa contador).
-    // None = sin NVS partition (no persistimos config WiFi en flash).
-    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    // Pasar la partición NVS le permite al driver cachear la config WiFi
+    // en flash, que es lo que usa esp_wifi_connect() (llamado por el
+    // handler de reconexión) para reintentar sin que nosotros repitamos
+    // set_configuration().
+    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), nvs)?;
 
     // BlockingWifi wrappea el driver async en API síncrona.
     // Usa &mut (borrow) — NO toma ownership de esp_wifi.
-    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop)?;
+    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop.clone())?;
 
     // Configuración default para poder hacer start() y scan()
     wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
@@ -55,39 +377,269 @@ pub fn connect(
     info!("Starting WiFi...");
     wifi.start()?;
 
-    // ─── Scan de redes ───
+    apply_power_save(power_save)?;
 
-    // Escaneamos para encontrar el canal exacto del AP.
-    // Con el canal correcto, la conexión es más rápida.
-    info!("Scanning for networks...");
-    let ap_infos = wifi.scan()?;
+    // ─── IP estática (opcional) ───
+
+    // Debe aplicarse antes de connect() en ambos flujos (fast-connect y
+    // scan completo), reemplazando el cliente DHCP por defecto.
+    if let Some(cfg) = static_ip {
+        apply_static_ip(&mut wifi, cfg)?;
+    } else {
+        info!("No static IP configured, using DHCP");
+    }
+
+    // ─── Fast-connect (opcional) ───
+    //
+    // Si tenemos BSSID+canal+auth de una conexión previa exitosa, nos
+    // saltamos el scan() y vamos directo al AP conocido — esto es lo que
+    // ahorra los segundos de latencia. Si el intento falla (AP se movió
+    // de canal, cambió de BSSID, está apagado, etc.) caemos al flujo
+    // normal con scan completo.
+    let fast_result = if let Some(fc) = fast_connect {
+        info!(
+            "Fast-connect: probando BSSID {:02x?} en canal {} (sin scan)",
+            fc.bssid, fc.channel
+        );
 
-    let target_ap = ap_infos.into_iter().find(|ap| ap.ssid == ssid);
+        wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+            ssid: ssid.try_into().expect("SSID too long"),
+            password: password.try_into().expect("Password too long"),
+            bssid: Some(fc.bssid),
+            channel: Some(fc.channel),
+            auth_method: fc.auth_method,
+            ..Default::default()
+        }))?;
 
-    let channel = target_ap.as_ref().map(|ap| ap.channel);
+        match wifi.connect() {
+            Ok(()) => Some(fc),
+            Err(e) => {
+                warn!("Fast-connect falló ({:?}), haciendo scan completo", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
+    let connect_info = match fast_result {
+        Some(fc) => fc,
+        None => {
+            // ─── Scan de redes ───
+
+            // Escaneamos para encontrar el canal y BSSID exactos del AP.
+            // Con el canal correcto, la conexión es más rápida, y guardamos
+            // el BSSID para poder saltarnos el scan en el próximo boot.
+            info!("Scanning for networks...");
+            let ap_infos = wifi.scan()?;
+
+            let target_ap = ap_infos.into_iter().find(|ap| ap.ssid == ssid);
+
+            let channel = target_ap.as_ref().map(|ap| ap.channel);
+            let bssid = target_ap.as_ref().map(|ap| ap.bssid);
+
+            // Usamos el auth_method que el propio AP anuncia en vez de asumir
+            // WPA2Personal: de lo contrario una red abierta, WEP o WPA3-only
+            // rechaza la conexión por mismatch de seguridad. Sin password no
+            // tiene sentido autenticar, así que ahí forzamos `None` aunque el
+            // AP anuncie otra cosa. Si no encontramos el AP en el scan,
+            // WPA2Personal sigue siendo el fallback razonable (comportamiento
+            // de siempre).
+            let auth_method = if password.is_empty() {
+                AuthMethod::None
+            } else {
+                target_ap
+                    .as_ref()
+                    .and_then(|ap| ap.auth_method)
+                    .unwrap_or(AuthMethod::WPA2Personal)
+            };
+
+            info!(
+                "Found AP '{}' on channel {:?}, auth {:?}",
+                ssid,
+                channel.unwrap_or(0),
+                auth_method
+            );
+
+            // ─── Configurar con credenciales reales ───
+
+            wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+                ssid: ssid.try_into().expect("SSID too long"),
+                password: password.try_into().expect("Password too long"),
+                bssid,
+                channel,
+                auth_method,
+                ..Default::default()
+            }))?;
+
+            // connect() bloquea hasta autenticación con el router
+            info!("Connecting to '{}'...", ssid);
+            wifi.connect()?;
+
+            FastConnectInfo {
+                bssid: bssid.unwrap_or([0; 6]),
+                channel: channel.unwrap_or(0),
+                auth_method,
+            }
+        }
+    };
+
+    if static_ip.is_none() {
+        // wait_netif_up() bloquea hasta obtener IP por DHCP.
+        // Sin IP no podemos hacer nada en la red (ni HTTP, ni DNS).
+        info!("Waiting for DHCP lease...");
+        wifi.wait_netif_up()?;
+    }
+
+    let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
+
+    info!("WiFi connected!");
+    info!("IP: {}", ip_info.ip);
+    info!("Gateway: {}", ip_info.subnet.gateway);
+    info!("Mask: {}", ip_info.subnet.mask);
+
+    // Reconexión automática: se suscribe ahora (no antes) para que la
+    // primera conexión, hecha arriba, pase por el control de errores
+    // normal de connect() en vez de disparar un reintento en paralelo.
+    let reconnect_subscription = spawn_auto_reconnect(&sysloop, storage)?;
+
+    // Retornamos esp_wifi en un Box (heap allocation).
+    // BlockingWifi se dropea aquí, pero la conexión sigue porque
+    // el driver real (esp_wifi) sigue vivo en el Box. La subscription
+    // también debe mantenerse viva — si se dropea, el handler se
+    // desuscribe y la reconexión automática deja de funcionar. connect_info
+    // lo persiste el caller en NVS para el fast-connect del próximo boot.
+    Ok((Box::new(esp_wifi), reconnect_subscription, connect_info))
+}
+
+// ─── WPA2-Enterprise (EAP) ───
+
+/// Credenciales para una red WPA2-Enterprise (PEAP/TTLS/TLS), típica de
+/// universidades y oficinas. A diferencia de WPA2-Personal no hay una
+/// passphrase compartida: se autentica con identidad + usuario/contraseña
+/// dentro de un túnel TLS, validado opcionalmente contra un certificado
+/// CA del servidor RADIUS.
+pub struct EnterpriseCredentials<'a> {
+    /// Identidad EAP externa (viaja sin cifrar en el handshake inicial).
+    pub identity: &'a str,
+    /// Usuario dentro del túnel TLS (PEAP/TTLS) o identidad del cliente (TLS).
+    pub username: &'a str,
+    pub password: &'a str,
+    /// Identidad externa alternativa, para no exponer `username` fuera
+    /// del túnel TLS. Si es `None`, se usa `identity` para ambas cosas.
+    pub anonymous_identity: Option<&'a str>,
+    /// Certificado CA en PEM, null-terminated, para validar el servidor
+    /// RADIUS. Sin esto, ESP-IDF no valida el certificado del servidor.
+    pub ca_cert: Option<&'a [u8]>,
+}
+
+/// Conecta a una red WPA2-Enterprise (PEAP/TTLS/TLS).
+///
+/// Mismo flujo que `connect()` (scan para ubicar el canal, IP estática
+/// opcional, reconexión automática), pero configurando el cliente EAP de
+/// ESP-IDF antes de `wifi.connect()`. esp-idf-svc no expone un wrapper
+/// seguro para el cliente EAP, así que llamamos directamente a
+/// `esp_wifi_sta_wpa2_ent_*` (FFI) — igual que `spawn_auto_reconnect`
+/// llama a `esp_wifi_connect()` para lo que tampoco tiene wrapper.
+pub fn connect_enterprise(
+    ssid: &str,
+    creds: EnterpriseCredentials,
+    modem: impl peripheral::Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
+    sysloop: EspSystemEventLoop,
+    nvs: Option<EspDefaultNvsPartition>,
+    static_ip: Option<StaticIpConfig>,
+    power_save: PowerSaveMode,
+    storage: Arc<Mutex<SecureStorage>>,
+) -> Result<(
+    Box<EspWifi<'static>>,
+    esp_idf_svc::eventloop::EspSubscription<'static, System>,
+)> {
+    // ─── Validación de credenciales ───
+
+    if ssid.is_empty() {
+        bail!("WiFi SSID not configured");
+    }
+    if creds.identity.is_empty() || creds.username.is_empty() {
+        bail!("EAP identity/username not configured");
+    }
+
+    // ─── Crear driver WiFi ───
+
+    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), nvs)?;
+    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop.clone())?;
+
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+
+    info!("Starting WiFi...");
+    wifi.start()?;
+
+    apply_power_save(power_save)?;
+
+    // ─── Scan de redes ───
+
+    info!("Scanning for networks...");
+    let ap_infos = wifi.scan()?;
+    let channel = ap_infos.iter().find(|ap| ap.ssid == ssid).map(|ap| ap.channel);
     info!("Found AP '{}' on channel {:?}", ssid, channel.unwrap_or(0));
 
-    // ─── Configurar con credenciales reales ───
+    // ─── Configurar con SSID y auth WPA2-Enterprise ───
 
+    // Sin password: la autenticación real la hace el cliente EAP (abajo),
+    // no el handshake WPA2 de ClientConfiguration.
     wifi.set_configuration(&Configuration::Client(ClientConfiguration {
         ssid: ssid.try_into().expect("SSID too long"),
-        password: password.try_into().expect("Password too long"),
         channel,
-        auth_method,
+        auth_method: AuthMethod::WPA2Enterprise,
         ..Default::default()
     }))?;
 
+    // ─── Configurar cliente EAP ───
+
+    // set_identity/set_username/set_password toman (ptr, len) — no
+    // necesitan null terminator. set_ca_cert sí espera el PEM tal como
+    // lo entrega mbedTLS (incluyendo el null terminator del caller).
+    unsafe {
+        let identity = creds.anonymous_identity.unwrap_or(creds.identity);
+        esp_idf_svc::sys::esp_wifi_sta_wpa2_ent_set_identity(
+            identity.as_ptr(),
+            identity.len() as i32,
+        );
+        esp_idf_svc::sys::esp_wifi_sta_wpa2_ent_set_username(
+            creds.username.as_ptr(),
+            creds.username.len() as i32,
+        );
+        esp_idf_svc::sys::esp_wifi_sta_wpa2_ent_set_password(
+            creds.password.as_ptr(),
+            creds.password.len() as i32,
+        );
+
+        if let Some(ca_cert) = creds.ca_cert {
+            esp_idf_svc::sys::esp_wifi_sta_wpa2_ent_set_ca_cert(
+                ca_cert.as_ptr(),
+                ca_cert.len() as i32,
+            );
+        }
+
+        esp_idf_svc::sys::esp_wifi_sta_wpa2_ent_enable();
+    }
+
+    // ─── IP estática (opcional) ───
+
+    if let Some(cfg) = static_ip {
+        apply_static_ip(&mut wifi, cfg)?;
+    } else {
+        info!("No static IP configured, using DHCP");
+    }
+
     // ─── Conectar y obtener IP ───
 
-    // connect() bloquea hasta autenticación con el router
-    info!("Connecting to '{}'...", ssid);
+    info!("Connecting to '{}' (WPA2-Enterprise)...", ssid);
     wifi.connect()?;
 
-    // wait_netif_up() bloquea hasta obtener IP por DHCP
-    // Sin IP no podemos hacer nada en la red (ni HTTP, ni DNS)
-    info!("Waiting for DHCP lease...");
-    wifi.wait_netif_up()?;
+    if static_ip.is_none() {
+        info!("Waiting for DHCP lease...");
+        wifi.wait_netif_up()?;
+    }
 
     let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
 
@@ -96,8 +648,7 @@ pub fn connect(
     info!("Gateway: {}", ip_info.subnet.gateway);
     info!("Mask: {}", ip_info.subnet.mask);
 
-    // Retornamos esp_wifi en un Box (heap allocation).
-    // BlockingWifi se dropea aquí, pero la conexión sigue porque
-    // el driver real (esp_wifi) sigue vivo en el Box.
-    Ok(Box::new(esp_wifi))
+    let reconnect_subscription = spawn_auto_reconnect(&sysloop, storage)?;
+
+    Ok((Box::new(esp_wifi), reconnect_subscription))
 }